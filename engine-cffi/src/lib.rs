@@ -15,6 +15,27 @@ pub struct InputResult {
     pub char2: char,
 }
 
+/// Snapshot of the engine's Hanja/lookup candidate list, returned whenever
+/// `InputResult::ty` is `OpenCandidate` or `UpdateCandidate`.
+#[derive(Clone, Debug)]
+pub struct CandidateState {
+    pub candidates: Vec<String>,
+    pub selected: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreeditFeedback(pub u8);
+
+impl PreeditFeedback {
+    pub const UNDERLINE: u8 = 1 << 0;
+    pub const REVERSE: u8 = 1 << 1;
+    pub const HIGHLIGHT: u8 = 1 << 2;
+
+    pub fn contains(self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+}
+
 pub struct InputEngine {
     engine: *mut ffi::KimeInputEngine,
 }
@@ -47,6 +68,81 @@ impl InputEngine {
             }
         }
     }
+
+    /// The full composing string (e.g. an in-progress Hangul syllable),
+    /// valid until the next call that mutates the engine.
+    pub fn preedit_str(&self) -> &str {
+        unsafe {
+            let mut ptr = MaybeUninit::uninit();
+            let mut len = MaybeUninit::uninit();
+            ffi::kime_engine_preedit_str(self.engine, ptr.as_mut_ptr(), len.as_mut_ptr());
+            std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+                ptr.assume_init(),
+                len.assume_init(),
+            ))
+        }
+    }
+
+    /// Per-character style hint for `preedit_str`, one entry per char,
+    /// mirroring the bit-flags XIM's `Feedback` type uses.
+    pub fn preedit_feedback(&self) -> Vec<PreeditFeedback> {
+        unsafe {
+            let mut ptr = MaybeUninit::uninit();
+            let mut len = MaybeUninit::uninit();
+            ffi::kime_engine_preedit_feedback(self.engine, ptr.as_mut_ptr(), len.as_mut_ptr());
+            std::slice::from_raw_parts(ptr.assume_init(), len.assume_init())
+                .iter()
+                .copied()
+                .map(PreeditFeedback)
+                .collect()
+        }
+    }
+
+    /// Short label for the engine's current input mode (e.g. "한"/"A"),
+    /// suitable for a status-area indicator.
+    pub fn mode_name(&self) -> &str {
+        unsafe {
+            let mut ptr = MaybeUninit::uninit();
+            let mut len = MaybeUninit::uninit();
+            ffi::kime_engine_mode_name(self.engine, ptr.as_mut_ptr(), len.as_mut_ptr());
+            std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+                ptr.assume_init(),
+                len.assume_init(),
+            ))
+        }
+    }
+
+    /// Current Hanja/lookup candidate list and selected index, if a
+    /// candidate window should be showing.
+    pub fn candidate_state(&self) -> Option<CandidateState> {
+        unsafe {
+            let len = ffi::kime_engine_candidate_len(self.engine);
+
+            if len == 0 {
+                return None;
+            }
+
+            let mut candidates = Vec::with_capacity(len as usize);
+
+            for i in 0..len {
+                let mut ptr = MaybeUninit::uninit();
+                let mut byte_len = MaybeUninit::uninit();
+                ffi::kime_engine_candidate_at(self.engine, i, ptr.as_mut_ptr(), byte_len.as_mut_ptr());
+                candidates.push(
+                    std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+                        ptr.assume_init(),
+                        byte_len.assume_init(),
+                    ))
+                    .to_string(),
+                );
+            }
+
+            Some(CandidateState {
+                candidates,
+                selected: ffi::kime_engine_candidate_index(self.engine) as usize,
+            })
+        }
+    }
 }
 
 impl Drop for InputEngine {
@@ -85,6 +181,13 @@ impl Config {
             ffi::kime_config_gtk_commit_english(self.config) != 0
         }
     }
+
+    /// Modifier bits the XIM server should strip before comparing against
+    /// Shift, so locking modifiers (NumLock/CapsLock) or a layout's own
+    /// Level3/AltGr bit don't force a reset of in-progress composition.
+    pub fn xim_ignored_modifier_mask(&self) -> u32 {
+        unsafe { ffi::kime_config_xim_ignored_modifier_mask(self.config) }
+    }
 }
 
 impl Drop for Config {
@@ -0,0 +1,174 @@
+use std::num::NonZeroU32;
+
+use crate::xfont::{encode_text16, open_font};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConfigureNotifyEvent, ConnectionExt, Rectangle},
+    xcb_ffi::XCBConnection,
+};
+
+/// Vertical list of Hanja/lookup candidates, drawn next to the preedit spot.
+///
+/// Mirrors `PeWindow`'s window/font plumbing but renders multiple lines with
+/// the currently selected entry highlighted.
+pub struct CandidateWindow {
+    window: NonZeroU32,
+    gc: u32,
+    font: u32,
+    candidates: Vec<String>,
+    selected: usize,
+}
+
+impl CandidateWindow {
+    pub fn new(
+        conn: &XCBConnection,
+        font_name: &str,
+        app_win: u32,
+        (x, y): (i32, i32),
+        screen_num: usize,
+        candidates: Vec<String>,
+        selected: usize,
+    ) -> Result<Self, xim::ServerError> {
+        let setup = conn.setup();
+        let screen = &setup.roots[screen_num];
+        let window = conn.generate_id()?;
+        let font = open_font(conn, font_name)?;
+
+        let _ = app_win;
+
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            x as i16,
+            y as i16,
+            120,
+            20,
+            1,
+            x11rb::protocol::xproto::WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &x11rb::protocol::xproto::CreateWindowAux::new()
+                .background_pixel(screen.white_pixel)
+                .override_redirect(1)
+                .event_mask(
+                    x11rb::protocol::xproto::EventMask::EXPOSURE
+                        | x11rb::protocol::xproto::EventMask::STRUCTURE_NOTIFY,
+                ),
+        )?;
+
+        let gc = conn.generate_id()?;
+        conn.create_gc(
+            gc,
+            window,
+            &x11rb::protocol::xproto::CreateGCAux::new()
+                .foreground(screen.black_pixel)
+                .background(screen.white_pixel)
+                .font(font),
+        )?;
+
+        let window = NonZeroU32::new(window).unwrap();
+
+        let mut this = Self {
+            window,
+            gc,
+            font,
+            candidates,
+            selected,
+        };
+
+        this.resize(conn)?;
+        conn.map_window(this.window.get())?;
+        conn.flush()?;
+
+        Ok(this)
+    }
+
+    pub fn window(&self) -> NonZeroU32 {
+        self.window
+    }
+
+    /// Current pixel height of the list, for stacking other windows below it.
+    pub fn height(&self) -> i32 {
+        20 * self.candidates.len().max(1) as i32
+    }
+
+    /// Replace the candidate list/selection and repaint.
+    pub fn update(
+        &mut self,
+        conn: &XCBConnection,
+        candidates: Vec<String>,
+        selected: usize,
+    ) -> Result<(), xim::ServerError> {
+        self.candidates = candidates;
+        self.selected = selected;
+        self.resize(conn)?;
+        self.expose(conn)?;
+        Ok(())
+    }
+
+    fn resize(&mut self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        let line_height = 20;
+        let width = self
+            .candidates
+            .iter()
+            .map(|c| c.chars().count() as u16 * 10 + 20)
+            .max()
+            .unwrap_or(120);
+        let height = (self.candidates.len() as u16 * line_height).max(line_height);
+
+        conn.configure_window(
+            self.window.get(),
+            &x11rb::protocol::xproto::ConfigureWindowAux::new()
+                .width(width as u32)
+                .height(height as u32),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn expose(&mut self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        conn.clear_area(false, self.window.get(), 0, 0, 0, 0)?;
+
+        for (i, candidate) in self.candidates.iter().enumerate() {
+            let label = format!("{}. {}", i + 1, candidate);
+            conn.image_text16(
+                self.window.get(),
+                self.gc,
+                4,
+                16 + 20 * i as i16,
+                &encode_text16(&label),
+            )?;
+        }
+
+        if let Some(rect) = self.selection_rect() {
+            conn.poly_rectangle(self.window.get(), self.gc, &[rect])?;
+        }
+
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    fn selection_rect(&self) -> Option<Rectangle> {
+        if self.candidates.is_empty() {
+            return None;
+        }
+
+        Some(Rectangle {
+            x: 1,
+            y: 1 + 20 * self.selected as i16,
+            width: 118,
+            height: 19,
+        })
+    }
+
+    pub fn configure_notify(&mut self, _e: ConfigureNotifyEvent) {}
+
+    pub fn clean(self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        conn.free_gc(self.gc)?;
+        conn.close_font(self.font)?;
+        conn.destroy_window(self.window.get())?;
+        conn.flush()?;
+        Ok(())
+    }
+}
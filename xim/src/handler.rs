@@ -1,9 +1,14 @@
 use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
 
+use crate::candidate_window::CandidateWindow;
 use crate::pe_window::PeWindow;
+use crate::status_window::StatusWindow;
 use ahash::AHashMap;
 use x11rb::{
-    protocol::xproto::{ConfigureNotifyEvent, EventMask, KeyPressEvent, KEY_PRESS_EVENT},
+    protocol::xproto::{
+        ConfigureNotifyEvent, ConnectionExt, EventMask, KeyPressEvent, KEY_PRESS_EVENT,
+    },
     xcb_ffi::XCBConnection,
 };
 use xim::{
@@ -11,11 +16,33 @@ use xim::{
     InputStyle, Server, ServerHandler,
 };
 
-use kime_engine_cffi::{Config, InputEngine, KimeInputResultType};
+use kime_engine_cffi::{Config, InputEngine, KimeInputResultType, PreeditFeedback};
+
+fn feedback_runs(raw: &[PreeditFeedback]) -> Vec<xim::Feedback> {
+    raw.iter()
+        .map(|f| {
+            let mut feedback = xim::Feedback::empty();
+
+            if f.contains(PreeditFeedback::UNDERLINE) {
+                feedback |= xim::Feedback::UNDERLINE;
+            }
+            if f.contains(PreeditFeedback::REVERSE) {
+                feedback |= xim::Feedback::REVERSE;
+            }
+            if f.contains(PreeditFeedback::HIGHLIGHT) {
+                feedback |= xim::Feedback::HIGHLIGHT;
+            }
+
+            feedback
+        })
+        .collect()
+}
 
 pub struct KimeData {
     engine: InputEngine,
     pe: Option<NonZeroU32>,
+    candidate: Option<NonZeroU32>,
+    status: Option<NonZeroU32>,
 }
 
 impl KimeData {
@@ -23,12 +50,28 @@ impl KimeData {
         Self {
             engine: InputEngine::new(),
             pe: None,
+            candidate: None,
+            status: None,
         }
     }
 }
 
+/// How often `handle_forward_event` re-checks for orphaned windows, on top
+/// of the immediate sweep on client disconnect.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Height of a single-line auxiliary window (preedit/status), used to stack
+/// windows that share the same `preedit_spot` instead of overlapping.
+const LINE_HEIGHT: i32 = 20;
+
 pub struct KimeHandler {
     preedit_windows: AHashMap<NonZeroU32, PeWindow>,
+    candidate_windows: AHashMap<NonZeroU32, CandidateWindow>,
+    status_windows: AHashMap<NonZeroU32, StatusWindow>,
+    // target client window each of the windows above is positioned relative
+    // to, so a periodic sweep can tell whether the client is still alive
+    app_wins: AHashMap<NonZeroU32, u32>,
+    next_sweep: Instant,
     config: Config,
     screen_num: usize,
 }
@@ -37,17 +80,98 @@ impl KimeHandler {
     pub fn new(screen_num: usize, config: Config) -> Self {
         Self {
             preedit_windows: AHashMap::new(),
+            candidate_windows: AHashMap::new(),
+            status_windows: AHashMap::new(),
+            app_wins: AHashMap::new(),
+            next_sweep: Instant::now() + SWEEP_INTERVAL,
             config,
             screen_num,
         }
     }
+
+    /// Run `sweep_orphaned_windows` at most once per `SWEEP_INTERVAL`. This
+    /// is the "periodic sweep" half of orphan recovery: `handle_disconnect`
+    /// catches a clean transport teardown immediately, but a client that
+    /// hangs or whose connection dies without the server ever observing a
+    /// disconnect event still needs its windows reaped eventually.
+    fn maybe_sweep(&mut self, conn: &XCBConnection) {
+        let now = Instant::now();
+
+        if now >= self.next_sweep {
+            self.sweep_orphaned_windows(conn);
+            self.next_sweep = now + SWEEP_INTERVAL;
+        }
+    }
+
+    /// Drop windows whose target `app_win` no longer exists on the X
+    /// server. Call this from a disconnect hook or a periodic timer to
+    /// recover from clients that crash or disappear without a clean
+    /// DestroyIC, which would otherwise leak `PeWindow`/`CandidateWindow`/
+    /// `StatusWindow` X resources forever.
+    pub fn sweep_orphaned_windows(&mut self, conn: &XCBConnection) {
+        let stale: Vec<NonZeroU32> = self
+            .app_wins
+            .iter()
+            .filter(|(_, &app_win)| {
+                conn.get_window_attributes(app_win)
+                    .ok()
+                    .and_then(|cookie| cookie.reply().ok())
+                    .is_none()
+            })
+            .map(|(&win, _)| win)
+            .collect();
+
+        for win in stale {
+            self.app_wins.remove(&win);
+
+            if let Some(pe) = self.preedit_windows.remove(&win) {
+                log::trace!("Reap orphaned PeWindow: {}", win);
+                let _ = pe.clean(conn);
+            } else if let Some(candidate) = self.candidate_windows.remove(&win) {
+                log::trace!("Reap orphaned CandidateWindow: {}", win);
+                let _ = candidate.clean(conn);
+            } else if let Some(status) = self.status_windows.remove(&win) {
+                log::trace!("Reap orphaned StatusWindow: {}", win);
+                let _ = status.clean(conn);
+            }
+        }
+    }
 }
 
 impl KimeHandler {
-    pub fn expose(&mut self, window: u32) {
+    // Fixed stacking order for the auxiliary windows that all anchor off
+    // `ic.preedit_spot()`: status on top, then preedit, then the candidate
+    // list at the bottom. Each `*_height` helper returns 0 when that window
+    // isn't currently open for `ic`, so the offset of a new window is the
+    // sum of the heights of whichever windows precede it in this order and
+    // are open when it is created. Offsets are only computed at creation:
+    // windows already open are not moved when a window that precedes them
+    // opens or closes later.
+    fn status_height(&self, ic: &xim::InputContext<KimeData>) -> i32 {
+        ic.user_data
+            .status
+            .as_ref()
+            .and_then(|w| self.status_windows.get(w))
+            .map(|w| w.height())
+            .unwrap_or(0)
+    }
+
+    fn preedit_height(&self, ic: &xim::InputContext<KimeData>) -> i32 {
+        if ic.user_data.pe.is_some() {
+            LINE_HEIGHT
+        } else {
+            0
+        }
+    }
+
+    pub fn expose(&mut self, conn: &XCBConnection, window: u32) {
         if let Some(win) = NonZeroU32::new(window) {
             if let Some(pe) = self.preedit_windows.get_mut(&win) {
                 pe.expose();
+            } else if let Some(candidate) = self.candidate_windows.get_mut(&win) {
+                let _ = candidate.expose(conn);
+            } else if let Some(status) = self.status_windows.get_mut(&win) {
+                let _ = status.expose(conn);
             }
         }
     }
@@ -56,6 +180,10 @@ impl KimeHandler {
         if let Some(win) = NonZeroU32::new(e.window) {
             if let Some(pe) = self.preedit_windows.get_mut(&win) {
                 pe.configure_notify(e);
+            } else if let Some(candidate) = self.candidate_windows.get_mut(&win) {
+                candidate.configure_notify(e);
+            } else if let Some(status) = self.status_windows.get_mut(&win) {
+                status.configure_notify(e);
             }
         }
     }
@@ -64,31 +192,47 @@ impl KimeHandler {
         &mut self,
         server: &mut X11rbServer<XCBConnection>,
         ic: &mut xim::InputContext<KimeData>,
-        ch: char,
     ) -> Result<(), xim::ServerError> {
+        let s = ic.user_data.engine.preedit_str().to_string();
+
         if ic.input_style().contains(InputStyle::PREEDIT_CALLBACKS) {
-            log::trace!("Preedit callback {}", ch);
-            // on-the-spot send preedit callback
-            let mut buf = [0; 4];
-            let s = ch.encode_utf8(&mut buf);
-            server.preedit_draw(ic, s)?;
-        } else if let Some(pe) = ic.user_data.pe.as_mut() {
-            // off-the-spot draw in server (already have pe_window)
-            self.preedit_windows.get_mut(pe).unwrap().set_preedit(ch);
+            log::trace!("Preedit callback {}", s);
+            // on-the-spot send preedit callback, with per-char feedback so the
+            // composing jamo is visually distinct from already committed text
+            let feedback = feedback_runs(&ic.user_data.engine.preedit_feedback());
+            server.preedit_draw_feedback(ic, &s, &feedback)?;
+        } else if let Some(pe) = ic.user_data.pe {
+            // off-the-spot draw in server (already have pe_window), unless
+            // the orphan sweep already reaped it out from under this IC —
+            // in that case fall back to the "no window yet" path below
+            match self.preedit_windows.get_mut(&pe) {
+                Some(w) => w.set_preedit(&s),
+                None => {
+                    ic.user_data.pe = None;
+                    return self.preedit(server, ic);
+                }
+            }
         } else {
-            // off-the-spot draw in server
+            // off-the-spot draw in server; a status window may already be
+            // showing at this spot (handle_set_focus opens it before any
+            // typing happens), so stack below it per the fixed status ->
+            // preedit -> candidate order
+            let (x, y) = ic.preedit_spot();
+            let y_offset = self.status_height(ic);
+
             let mut pe = PeWindow::new(
                 server.conn(),
                 self.config.xim_font_name(),
                 ic.app_win(),
-                ic.preedit_spot(),
+                (x, y + y_offset),
                 self.screen_num,
             )?;
 
-            pe.set_preedit(ch);
+            pe.set_preedit(&s);
 
             ic.user_data.pe = Some(pe.window());
 
+            self.app_wins.insert(pe.window(), ic.app_win());
             self.preedit_windows.insert(pe.window(), pe);
         }
 
@@ -108,12 +252,150 @@ impl KimeHandler {
         Ok(())
     }
 
+    fn open_candidate(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        ic: &mut xim::InputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        let state = match ic.user_data.engine.candidate_state() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        if let Some(candidate) = ic.user_data.candidate {
+            // as in `preedit`, the sweep may have reaped this window while
+            // the IC still thinks it's open
+            match self.candidate_windows.get_mut(&candidate) {
+                Some(w) => w.update(server.conn(), state.candidates, state.selected)?,
+                None => {
+                    ic.user_data.candidate = None;
+                    return self.open_candidate(server, ic);
+                }
+            }
+        } else {
+            // a status and/or preedit window may already be showing at the
+            // same spot, so stack the candidate list below both, per the
+            // fixed status -> preedit -> candidate order
+            let (x, y) = ic.preedit_spot();
+            let y_offset = self.status_height(ic) + self.preedit_height(ic);
+
+            let candidate = CandidateWindow::new(
+                server.conn(),
+                self.config.xim_font_name(),
+                ic.app_win(),
+                (x, y + y_offset),
+                self.screen_num,
+                state.candidates,
+                state.selected,
+            )?;
+
+            ic.user_data.candidate = Some(candidate.window());
+            self.app_wins.insert(candidate.window(), ic.app_win());
+            self.candidate_windows.insert(candidate.window(), candidate);
+        }
+
+        Ok(())
+    }
+
+    fn close_candidate(
+        &mut self,
+        c: &XCBConnection,
+        ic: &mut xim::InputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if let Some(candidate) = ic.user_data.candidate.take() {
+            self.app_wins.remove(&candidate);
+
+            if let Some(w) = self.candidate_windows.remove(&candidate) {
+                log::trace!("Destory CandidateWindow: {}", w.window());
+                w.clean(c)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show_status(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        ic: &mut xim::InputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if !ic.input_style().contains(InputStyle::STATUS_AREA) {
+            return Ok(());
+        }
+
+        let mode = ic.user_data.engine.mode_name().to_string();
+
+        if let Some(status) = ic.user_data.status {
+            // as in `preedit`, the sweep may have reaped this window while
+            // the IC still thinks it's open
+            match self.status_windows.get_mut(&status) {
+                Some(w) => w.set_mode(server.conn(), mode)?,
+                None => {
+                    ic.user_data.status = None;
+                    return self.show_status(server, ic);
+                }
+            }
+        } else {
+            // the status window is always first in the fixed status ->
+            // preedit -> candidate stacking order, so it never needs to
+            // offset below either of the other two; a preedit or candidate
+            // window that is already open is not moved down to make room
+            let (x, y) = ic.preedit_spot();
+
+            let status = StatusWindow::new(
+                server.conn(),
+                self.config.xim_font_name(),
+                ic.app_win(),
+                (x, y),
+                self.screen_num,
+                mode,
+            )?;
+
+            ic.user_data.status = Some(status.window());
+            self.app_wins.insert(status.window(), ic.app_win());
+            self.status_windows.insert(status.window(), status);
+        }
+
+        Ok(())
+    }
+
+    fn refresh_status(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        ic: &mut xim::InputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if ic.user_data.status.is_some() {
+            self.show_status(server, ic)?;
+        }
+
+        Ok(())
+    }
+
+    fn hide_status(
+        &mut self,
+        c: &XCBConnection,
+        ic: &mut xim::InputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if let Some(status) = ic.user_data.status.take() {
+            self.app_wins.remove(&status);
+
+            if let Some(w) = self.status_windows.remove(&status) {
+                log::trace!("Destory StatusWindow: {}", w.window());
+                w.clean(c)?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn clear_preedit(
         &mut self,
         c: &XCBConnection,
         ic: &mut xim::InputContext<KimeData>,
     ) -> Result<(), xim::ServerError> {
         if let Some(pe) = ic.user_data.pe.take() {
+            self.app_wins.remove(&pe);
+
             // off-the-spot draw in server
             if let Some(w) = self.preedit_windows.remove(&pe) {
                 log::trace!("Destory PeWindow: {}", w.window());
@@ -121,6 +403,8 @@ impl KimeHandler {
             }
         }
 
+        self.close_candidate(c, ic)?;
+
         Ok(())
     }
 
@@ -171,6 +455,14 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
         Ok(())
     }
 
+    fn handle_disconnect(&mut self, server: &mut X11rbServer<XCBConnection>) {
+        // A crashed/killed client never sends DestroyIC, so its PeWindow/
+        // CandidateWindow/StatusWindow would otherwise leak. We don't get
+        // told which ICs belonged to the departed client here, so fall back
+        // to the same staleness check the periodic sweep uses.
+        self.sweep_orphaned_windows(server.conn());
+    }
+
     fn handle_set_ic_values(
         &mut self,
         _server: &mut X11rbServer<XCBConnection>,
@@ -223,20 +515,29 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
             return Ok(false);
         }
 
+        self.maybe_sweep(server.conn());
+
+        // strip locking/layer modifiers (NumLock, CapsLock, AltGr, ...) the
+        // user configured to be ignored before checking for "other modifiers
+        // than shift", so they don't force-reset the engine
+        let state = xev.state as u32 & !self.config.xim_ignored_modifier_mask();
+
         // other modifiers then shift
-        if xev.state & (!0x1) != 0 {
+        if state & !0x1 != 0 {
             self.reset(server, input_context)?;
             return Ok(false);
         }
 
-        let ret = input_context.user_data.engine.press_key(
-            &self.config,
-            xev.detail as u16,
-            xev.state as u32,
-        );
+        let ret =
+            input_context
+                .user_data
+                .engine
+                .press_key(&self.config, xev.detail as u16, state);
 
         log::trace!("{:?}", ret);
 
+        self.refresh_status(server, input_context)?;
+
         match ret.ty {
             KimeInputResultType::Bypass => Ok(false),
             KimeInputResultType::Consume => Ok(true),
@@ -262,11 +563,19 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
             }
             KimeInputResultType::CommitPreedit => {
                 self.commit(server, input_context, ret.char1)?;
-                self.preedit(server, input_context, ret.char2)?;
+                self.preedit(server, input_context)?;
                 Ok(true)
             }
             KimeInputResultType::Preedit => {
-                self.preedit(server, input_context, ret.char1)?;
+                self.preedit(server, input_context)?;
+                Ok(true)
+            }
+            KimeInputResultType::OpenCandidate | KimeInputResultType::UpdateCandidate => {
+                self.open_candidate(server, input_context)?;
+                Ok(true)
+            }
+            KimeInputResultType::CloseCandidate => {
+                self.close_candidate(server.conn(), input_context)?;
                 Ok(true)
             }
         }
@@ -280,7 +589,27 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
         log::info!("destroy_ic");
 
         if let Some(pe) = input_context.user_data.pe {
-            self.preedit_windows.remove(&pe).unwrap().clean(&*server)?;
+            self.app_wins.remove(&pe);
+            // the periodic/disconnect sweep may have already reaped this
+            // window if the client went away uncleanly, so don't assume
+            // it's still here
+            if let Some(w) = self.preedit_windows.remove(&pe) {
+                w.clean(&*server)?;
+            }
+        }
+
+        if let Some(candidate) = input_context.user_data.candidate {
+            self.app_wins.remove(&candidate);
+            if let Some(w) = self.candidate_windows.remove(&candidate) {
+                w.clean(&*server)?;
+            }
+        }
+
+        if let Some(status) = input_context.user_data.status {
+            self.app_wins.remove(&status);
+            if let Some(w) = self.status_windows.remove(&status) {
+                w.clean(&*server)?;
+            }
         }
 
         Ok(())
@@ -305,10 +634,10 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
 
     fn handle_set_focus(
         &mut self,
-        _server: &mut X11rbServer<XCBConnection>,
-        _input_context: &mut xim::InputContext<Self::InputContextData>,
+        server: &mut X11rbServer<XCBConnection>,
+        input_context: &mut xim::InputContext<Self::InputContextData>,
     ) -> Result<(), xim::ServerError> {
-        Ok(())
+        self.show_status(server, input_context)
     }
 
     fn handle_unset_focus(
@@ -316,6 +645,7 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
         server: &mut X11rbServer<XCBConnection>,
         input_context: &mut xim::InputContext<Self::InputContextData>,
     ) -> Result<(), xim::ServerError> {
+        self.hide_status(server.conn(), input_context)?;
         self.reset(server, input_context)
     }
 }
@@ -0,0 +1,112 @@
+use std::num::NonZeroU32;
+
+use crate::xfont::{encode_text16, open_font};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConfigureNotifyEvent, ConnectionExt},
+    xcb_ffi::XCBConnection,
+};
+
+/// Small status-area indicator showing the engine's current mode (e.g.
+/// Hangul vs English), reusing `PeWindow`'s window/font plumbing.
+pub struct StatusWindow {
+    window: NonZeroU32,
+    gc: u32,
+    font: u32,
+    mode: String,
+}
+
+impl StatusWindow {
+    pub fn new(
+        conn: &XCBConnection,
+        font_name: &str,
+        app_win: u32,
+        (x, y): (i32, i32),
+        screen_num: usize,
+        mode: String,
+    ) -> Result<Self, xim::ServerError> {
+        let setup = conn.setup();
+        let screen = &setup.roots[screen_num];
+        let window = conn.generate_id()?;
+        let font = open_font(conn, font_name)?;
+
+        let _ = app_win;
+
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            x as i16,
+            y as i16,
+            20,
+            20,
+            1,
+            x11rb::protocol::xproto::WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &x11rb::protocol::xproto::CreateWindowAux::new()
+                .background_pixel(screen.white_pixel)
+                .override_redirect(1)
+                .event_mask(x11rb::protocol::xproto::EventMask::EXPOSURE),
+        )?;
+
+        let gc = conn.generate_id()?;
+        conn.create_gc(
+            gc,
+            window,
+            &x11rb::protocol::xproto::CreateGCAux::new()
+                .foreground(screen.black_pixel)
+                .background(screen.white_pixel)
+                .font(font),
+        )?;
+
+        let window = NonZeroU32::new(window).unwrap();
+
+        conn.map_window(window.get())?;
+        conn.flush()?;
+
+        let mut this = Self {
+            window,
+            gc,
+            font,
+            mode,
+        };
+        this.expose(conn)?;
+
+        Ok(this)
+    }
+
+    pub fn window(&self) -> NonZeroU32 {
+        self.window
+    }
+
+    /// Current pixel height, for stacking other windows below it.
+    pub fn height(&self) -> i32 {
+        20
+    }
+
+    pub fn set_mode(&mut self, conn: &XCBConnection, mode: String) -> Result<(), xim::ServerError> {
+        if self.mode != mode {
+            self.mode = mode;
+            self.expose(conn)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn expose(&mut self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        conn.clear_area(false, self.window.get(), 0, 0, 0, 0)?;
+        conn.image_text16(self.window.get(), self.gc, 4, 15, &encode_text16(&self.mode))?;
+        conn.flush()?;
+        Ok(())
+    }
+
+    pub fn configure_notify(&mut self, _e: ConfigureNotifyEvent) {}
+
+    pub fn clean(self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        conn.free_gc(self.gc)?;
+        conn.close_font(self.font)?;
+        conn.destroy_window(self.window.get())?;
+        conn.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use x11rb::{connection::Connection, protocol::xproto::Char2b, xcb_ffi::XCBConnection};
+
+/// Open the configured X core font and return its FID, ready to be bound to
+/// a GC with `CreateGCAux::font`/`ChangeGCAux::font`. Candidate/status
+/// windows need this (rather than drawing with whatever font the server
+/// defaults a freshly created GC to) since they render Hangul/Hanja text,
+/// not just ASCII.
+pub fn open_font(conn: &XCBConnection, font_name: &str) -> Result<u32, xim::ServerError> {
+    let font = conn.generate_id()?;
+    conn.open_font(font, font_name.as_bytes())?;
+    Ok(font)
+}
+
+/// Encode `s` as `Char2b`s for `image_text16`. X core fonts address glyphs
+/// as 16-bit (byte1, byte2) pairs, so a plain `image_text8` call (which
+/// kime used to make) truncates every Hangul/Hanja codepoint to garbage;
+/// this assumes the configured font is addressed on the BMP, matching how
+/// the preedit window's Unicode core font is set up.
+pub fn encode_text16(s: &str) -> Vec<Char2b> {
+    s.encode_utf16()
+        .map(|u| Char2b {
+            byte1: (u >> 8) as u8,
+            byte2: (u & 0xff) as u8,
+        })
+        .collect()
+}